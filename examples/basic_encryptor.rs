@@ -53,7 +53,8 @@
 
 use async_trait::async_trait;
 use docopt::Docopt;
-use self_encryption::{self, test_helpers, DataMap, SelfEncryptor, Storage, StorageError};
+use self_encryption::trust::{self, TrustError};
+use self_encryption::{self, read_stream, test_helpers, write_stream, DataMap, SelfEncryptor, Storage, StorageError};
 use serde::Deserialize;
 use std::{
     env,
@@ -61,7 +62,7 @@ use std::{
     fmt::{self, Display, Formatter},
     fs::{self, File},
     io::{Error as IoError, Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     string::String,
 };
 use tiny_keccak::sha3_256;
@@ -101,13 +102,21 @@ fn file_name(name: &[u8]) -> String {
 }
 
 #[derive(Debug)]
-struct DiskBasedStorageError {
-    io_error: IoError,
+enum DiskBasedStorageError {
+    Io(IoError),
+    UntrustedDirectory(TrustError),
 }
 
 impl Display for DiskBasedStorageError {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
-        write!(formatter, "I/O error getting/putting: {}", self.io_error)
+        match self {
+            DiskBasedStorageError::Io(error) => {
+                write!(formatter, "I/O error getting/putting: {}", error)
+            }
+            DiskBasedStorageError::UntrustedDirectory(error) => {
+                write!(formatter, "refusing to use chunk store: {}", error)
+            }
+        }
     }
 }
 
@@ -119,7 +128,13 @@ impl StdError for DiskBasedStorageError {
 
 impl From<IoError> for DiskBasedStorageError {
     fn from(error: IoError) -> DiskBasedStorageError {
-        DiskBasedStorageError { io_error: error }
+        DiskBasedStorageError::Io(error)
+    }
+}
+
+impl From<TrustError> for DiskBasedStorageError {
+    fn from(error: TrustError) -> DiskBasedStorageError {
+        DiskBasedStorageError::UntrustedDirectory(error)
     }
 }
 
@@ -142,6 +157,7 @@ impl Storage for DiskBasedStorage {
     type Error = DiskBasedStorageError;
 
     async fn get(&mut self, name: &[u8]) -> Result<Vec<u8>, DiskBasedStorageError> {
+        trust::ensure_trusted(Path::new(&self.storage_path))?;
         let path = self.calculate_path(name);
         let mut file = File::open(&path)?;
         let mut data = Vec::new();
@@ -151,6 +167,7 @@ impl Storage for DiskBasedStorage {
     }
 
     async fn put(&mut self, name: Vec<u8>, data: Vec<u8>) -> Result<(), DiskBasedStorageError> {
+        trust::ensure_trusted(Path::new(&self.storage_path))?;
         let path = self.calculate_path(&name);
         let mut file = File::create(&path)?;
 
@@ -188,32 +205,43 @@ async fn main() {
 
     if args.flag_encrypt && args.arg_target.is_some() {
         if let Ok(mut file) = File::open(unwrap!(args.arg_target.clone())) {
-            match file.metadata() {
-                Ok(metadata) => {
-                    if metadata.len() > self_encryption::MAX_FILE_SIZE as u64 {
-                        return println!(
-                            "File size too large {} is greater than 1GB",
-                            metadata.len()
-                        );
-                    }
-                }
+            let file_size = match file.metadata() {
+                Ok(metadata) => metadata.len(),
                 Err(error) => return println!("{}", error.to_string()),
-            }
+            };
 
-            let mut data = Vec::new();
-            match file.read_to_end(&mut data) {
-                Ok(_) => (),
-                Err(error) => return println!("{}", error.to_string()),
-            }
+            let data_map = if file_size > self_encryption::MAX_FILE_SIZE as u64 {
+                // Too large to buffer in memory: stream it chunk-window at a time instead.
+                println!(
+                    "File size {} exceeds the in-memory limit of 1GB, streaming instead",
+                    file_size
+                );
+                let reader = match tokio::fs::File::open(unwrap!(args.arg_target.clone())).await {
+                    Ok(reader) => reader,
+                    Err(error) => return println!("{}", error.to_string()),
+                };
+                let (data_map, old_storage) = write_stream(reader, storage)
+                    .await
+                    .expect("Streaming encryption shouldn't fail.");
+                storage = old_storage;
+                data_map
+            } else {
+                let mut data = Vec::new();
+                match file.read_to_end(&mut data) {
+                    Ok(_) => (),
+                    Err(error) => return println!("{}", error.to_string()),
+                }
 
-            let se = SelfEncryptor::new(storage, DataMap::None)
-                .expect("Encryptor construction shouldn't fail.");
-            se.write(&data, 0)
-                .await
-                .expect("Writing to encryptor shouldn't fail.");
-            let (data_map, old_storage) =
-                se.close().await.expect("Closing encryptor shouldn't fail.");
-            storage = old_storage;
+                let se = SelfEncryptor::new(storage, DataMap::None)
+                    .expect("Encryptor construction shouldn't fail.");
+                se.write(&data, 0)
+                    .await
+                    .expect("Writing to encryptor shouldn't fail.");
+                let (data_map, old_storage) =
+                    se.close().await.expect("Closing encryptor shouldn't fail.");
+                storage = old_storage;
+                data_map
+            };
 
             match File::create(data_map_file.clone()) {
                 Ok(mut file) => {
@@ -0,0 +1,122 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Mounts a directory of self-encrypted files as a real filesystem.
+//!
+//! Unlike `basic_encryptor`, which round-trips one file to/from disk per invocation, this keeps
+//! a `fuser::Filesystem` mounted so reads and writes translate directly into
+//! `SelfEncryptor::read`/`write` calls against the chunk store. Each open file handle fetches all
+//! of the file's chunks on its first read or write.
+//!
+//! Requires the `fuse` feature.
+
+#![cfg(feature = "fuse")]
+
+use async_trait::async_trait;
+use self_encryption::fuse::SelfEncryptingFs;
+use self_encryption::trust::{self, TrustError};
+use self_encryption::{Storage, StorageError};
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{Error as IoError, Read, Write};
+use std::path::PathBuf;
+
+#[derive(Debug)]
+enum DiskBasedStorageError {
+    Io(IoError),
+    UntrustedDirectory(TrustError),
+}
+
+impl Display for DiskBasedStorageError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self {
+            DiskBasedStorageError::Io(error) => {
+                write!(formatter, "I/O error getting/putting: {}", error)
+            }
+            DiskBasedStorageError::UntrustedDirectory(error) => {
+                write!(formatter, "refusing to use chunk store: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiskBasedStorageError {}
+
+impl From<IoError> for DiskBasedStorageError {
+    fn from(error: IoError) -> DiskBasedStorageError {
+        DiskBasedStorageError::Io(error)
+    }
+}
+
+impl From<TrustError> for DiskBasedStorageError {
+    fn from(error: TrustError) -> DiskBasedStorageError {
+        DiskBasedStorageError::UntrustedDirectory(error)
+    }
+}
+
+impl StorageError for DiskBasedStorageError {}
+
+#[derive(Clone)]
+struct DiskBasedStorage {
+    storage_path: PathBuf,
+}
+
+fn file_name(name: &[u8]) -> String {
+    name.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl DiskBasedStorage {
+    fn calculate_path(&self, name: &[u8]) -> PathBuf {
+        self.storage_path.join(file_name(name))
+    }
+}
+
+#[async_trait]
+impl Storage for DiskBasedStorage {
+    type Error = DiskBasedStorageError;
+
+    async fn get(&mut self, name: &[u8]) -> Result<Vec<u8>, DiskBasedStorageError> {
+        trust::ensure_trusted(&self.storage_path)?;
+        let mut file = File::open(self.calculate_path(name))?;
+        let mut data = Vec::new();
+        let _ = file.read_to_end(&mut data);
+        Ok(data)
+    }
+
+    async fn put(&mut self, name: Vec<u8>, data: Vec<u8>) -> Result<(), DiskBasedStorageError> {
+        trust::ensure_trusted(&self.storage_path)?;
+        let mut file = File::create(self.calculate_path(&name))?;
+        file.write_all(&data[..]).map_err(From::from)
+    }
+
+    async fn generate_address(&self, data: &[u8]) -> Vec<u8> {
+        tiny_keccak::sha3_256(data).to_vec()
+    }
+}
+
+fn main() {
+    let mountpoint = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            println!("Usage: fuse_mount <mountpoint>");
+            return;
+        }
+    };
+
+    let mut chunk_store_dir = env::temp_dir();
+    chunk_store_dir.push("chunk_store_test/");
+    let _ = fs::create_dir(chunk_store_dir.clone());
+
+    let fs = SelfEncryptingFs::new(move || DiskBasedStorage {
+        storage_path: chunk_store_dir.clone(),
+    });
+
+    println!("Mounting self-encrypted filesystem at {}", mountpoint);
+    fuser::mount2(fs, &mountpoint, &[]).expect("Mount failed.");
+}
@@ -0,0 +1,242 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! How a `SelfEncryptor` decides where to cut a file into chunks.
+
+use crate::{MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+
+/// A fixed table of random `u64`s used to build the gear hash's rolling fingerprint. Indexed by
+/// the byte currently entering the window.
+#[rustfmt::skip]
+const GEAR: [u64; 256] = [
+    0x161922C645CE50E8, 0xAD760CAFA1697B60, 0x3501FF44902CA50D, 0x417CB9A826D831DF,
+    0x99AF6F9B0C4476B6, 0x5D51F5F75B762C59, 0x66239E8C309A282B, 0x53E01F580916C5CB,
+    0xAA941016A4C2958B, 0x279993774594E137, 0x20E9A7A844BDACC0, 0x90EC693596CC8AB0,
+    0x4D7760D307367AFA, 0x4315096655B77A33, 0x0E907AA9D946B562, 0x1947CECFC10E24F3,
+    0x8A27BDF7C4B88166, 0x3989C8272F2AE095, 0xB7DC9A7F27F0B595, 0xA0F6C1D2ED13C145,
+    0xC54AD38A1E595BCE, 0xD87E930B7F41A756, 0x87EAD6B5C67EC06B, 0xA4353FABA48B2382,
+    0x19A42FC02250FF9D, 0x5BAEAC52832826B1, 0x862B3E793173997B, 0x60BA89BB02987253,
+    0xD51B395C4F12BD9A, 0x0BC7804037D52ADE, 0x42252510D604C41F, 0x29F45920A9F57C95,
+    0xA93B6EA467675DBC, 0x15C3AAABD5956AEC, 0xA5DAABF7C364C8E5, 0xD094CF38E10D9FAA,
+    0xAD06E37401370752, 0xCDB61E7BD233A525, 0x0A4BA189D018C8D3, 0x50B327159DB36439,
+    0x82A6283919AE345E, 0xCBE4FEC009A705BC, 0x00140BC367F632B3, 0xC01390DFAF502656,
+    0xE4A211A9598495BF, 0x2DE60A74AC7442E6, 0x7C80A5D8393D87DC, 0x0042F9E8AD284FD5,
+    0x1E86AE8DAE777E7B, 0x056B110D49D7A50E, 0x0CB3EA3F164075AE, 0x810C2241D09BE6D9,
+    0x8C3E2645B1F287D0, 0xD1E311A47F9CD5F8, 0xCE8D06C14B42138D, 0xF655D4C61563800D,
+    0x2B83B4FACEE21349, 0xFF5070D67C85F362, 0xFFF81FE0B509FD83, 0x26584FD1187D611C,
+    0xA339DEF8905CC9B6, 0x062D2657944BAF3C, 0x53395A748D962C4B, 0xADFC499F2A938342,
+    0x7EA69ED006AF8BD7, 0x8A2D3E828F6D3AE5, 0x32FB0973D630265D, 0x4051FE43C4B522AE,
+    0x082C3A7AC6F2B2DA, 0x0C3A17D99DF22145, 0xF6445251C28D637B, 0x9975C19CF44AFFDB,
+    0xB35F858BD5A4C400, 0x698F51EB4B966AA9, 0x825A83FAD5F42F53, 0xB1A1C87A8E370A11,
+    0xDD78E2D4F2BEFFBC, 0xDE74C9244AE698F4, 0x853315DF4F1B7C7A, 0x5953CF89DA9626E9,
+    0x7EF1AFF252B419A7, 0x0D7C263366FA669E, 0x8576AAC3174E2232, 0x9C20825CD0A0E128,
+    0x922A277C96F9A79E, 0x66FE071AA89214D5, 0x28E26D7561F3016D, 0x08BB2D9D88BA3BE2,
+    0xB1B00E7B7DD5F20C, 0x5C5B6B824C2705AE, 0x9F6535D60528FB6C, 0x50AB140E38A246C6,
+    0x993B4BF586E84635, 0x44DFC222AF3EF96D, 0xAAB7732237AF2BCA, 0xDE089459F29E2AAF,
+    0xEB399EC3F5FAA893, 0x86BC73B51214AEFB, 0x3235A8D4E6B2B330, 0x6C98D4263AA01342,
+    0xEBA2C848FBF2F151, 0xF0617B36BDEF52F8, 0x7359334C5CC1D837, 0xCA488D0A3E805164,
+    0x557EDCF42586AA06, 0x831A3DBF422EBDB6, 0x0B7183F2AF6DEFC7, 0x3CA78D39E1A1A93D,
+    0x7D96C744610C034E, 0xAF43C1F572B365D4, 0xA0A90B7E6688FAAA, 0x1DD7168C3A6B4C74,
+    0x08426523307A1662, 0xEBE9ADEF78634E13, 0x7DA4310DDC823B8B, 0xDA579BF86FAE8B5A,
+    0xF653A134A4C747DC, 0xBC5486ADDAB05206, 0x91D48852D77F8C1C, 0xFFDC36128B720421,
+    0x696576BE9BD2F14C, 0x36C0FFBEDD4BDF79, 0x0D80D05B8E4FDF8F, 0x8BE7B9E56060C921,
+    0xFC5EAA037B74FAA7, 0xB6A9C94F46D601AD, 0x203F082946B4A0F6, 0x8E059F98E9C6069B,
+    0xD5B54BD28A19ACB8, 0xB343DD5A78F8B450, 0x36079F11691EE4BB, 0xC49F5FBDC6610839,
+    0x31338B7FDE79CA2E, 0x22668F106FF6BFF1, 0x717BE48A0921E6A4, 0xD3005C7D06B347A7,
+    0x88ADCBA352C0AA12, 0x0D727F23D654948C, 0x8DA856C2FA827FE8, 0x7826FC59DDBBC97F,
+    0x25557D00E33333DD, 0x6033AFF71EBBE4EC, 0x1C1C81BB063415A8, 0x2BA93BA66CE2F230,
+    0x33B8BA7D7C707A7C, 0x7FAFA11DB8782F26, 0x24223FA0D0736B12, 0xA90E63B82C2F481E,
+    0x5A6B12258C9920B5, 0xFF2304EEDE1531E4, 0x84FE097FDE1D8469, 0xC8992DCE1397403B,
+    0x4846E5EE33AC3FB2, 0x8404322637000BBC, 0x09D6006A1A5525D6, 0xD605DB240DD49E26,
+    0xCF13D9C29BC3E6C6, 0xDC5339EE61466F5E, 0x76DE1C04FBD26E72, 0xD285FEBFE53EE592,
+    0xED8852011245BA89, 0xA34DAE9383E4FED1, 0x3CE937EDDC675DF6, 0x6C0ECED66A6F703F,
+    0xB99DF75E3EB2DE36, 0x482B5A5739286E35, 0x12471E12223F1D69, 0x9A195B06398C4375,
+    0x601B91DE3551443F, 0xE207C680DDFCA9D8, 0xBDDE1DD799D22472, 0x1365AE8C8E0463E3,
+    0xBBBF5C35A8301CA6, 0xDDBFA7323A79E77A, 0x975795D03753999B, 0xB42D170F98A37694,
+    0x873CCA3F004FA35F, 0x6426BE49467AD445, 0x82F3F34340C65372, 0xEAAC60CF55373F10,
+    0x7D8BC4A13793EF8F, 0x36BE91BDBA01424A, 0xE224ABB895D92EF4, 0x24A827201FFFECAA,
+    0xC60F8957D003E7E3, 0xA2DCE8FEED8EF8D3, 0x02D8A2C1DA0325A3, 0xA3D3A8C5FCCEE46A,
+    0x47D0D7C1880BD7F0, 0xAA24C34DFD59D363, 0xB47A9CB39D5B1E88, 0xD043E700AADDC81E,
+    0xF4382B6A43EDB55E, 0x371B1D53C01B8623, 0x42EE771782290D54, 0xFE8ADC45EE9674E1,
+    0x275EBD3DE2960FAE, 0x6F5393514F0C4205, 0x18DE42FBF438DDDB, 0x15EE1B0BAC1032ED,
+    0xFBC48A0E9A8BFAF0, 0x6CD2C9B8B2DDBFDC, 0x1FE0843E20A62ED4, 0xEEBBDFC0D8E95EDE,
+    0xCE56A65BBA2C8FE1, 0xA9C362010C4B727B, 0xB960D31D45608CD6, 0x129F546F0BB74D08,
+    0x386B7BBC401D5186, 0x962F45D44EADBBD4, 0x15B43F281C01563D, 0x0AE2346188F2806E,
+    0x819C7FD6E1AD7369, 0x17493BD4A5004BF7, 0x210D8AAD5939712B, 0x4870B197D4236315,
+    0x68A0F7011736ADBF, 0x503F2B65D8B2F13B, 0x8094A466DD35C927, 0xC3808A841A80F20A,
+    0x7AA622D21FDEBD73, 0xEBE6E4092686B39E, 0xE7D85F2A14EAA9C9, 0x07D7E8260A482653,
+    0x53FA24E731FBCFB6, 0x60F18718978E354F, 0xEECE5A82BB599EC9, 0x1212A7BCAE5E3015,
+    0x13A65FE41102C51E, 0x3DB1B71BE310C0E3, 0x79D8E260590BE224, 0x17B100A3AC6BD71A,
+    0x7D6FA19714BAAE33, 0x4FB5FAE13CC57BCF, 0x49D56DA2B2FAC5C6, 0x774D14C98E1B7C2B,
+    0xD58C4556D4526AEA, 0xAAD2D192B58B0134, 0x9679886E33440FC4, 0x3CEC22A3CB9A95EE,
+    0x4CA0258EC42AD0ED, 0x1D0AE54ACCD4B9C6, 0xDB41A92694E74A2F, 0x3A1D372B6859DB2F,
+    0x5D99F4609BCB4E69, 0xCCF1403B250CF1BC, 0xCEFB33A79BC86423, 0xF115F56DD10738B8,
+    0x22525C63B311797A, 0xDB064656F83E2935, 0x2C83E48C640C0037, 0x9B354B795E8858C1,
+    0x44BFB35F5C988406, 0x5191422A8DAFB040, 0x71854A3C39C71EE8, 0xEA2BE3A8ADBD94DA,
+];
+
+/// How a `SelfEncryptor` decides where to cut a file into chunks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChunkingStrategy {
+    /// Splits the file into a minimum of three roughly-equal fixed-size chunks, as determined
+    /// purely by total file length. Simple, but a single inserted byte shifts every boundary
+    /// after it.
+    FixedSize,
+    /// Content-defined chunking: boundaries fall at data-dependent positions (runs of a gear
+    /// hash hitting a mask), so unchanged regions of an edited file keep producing identical
+    /// chunks. `min_size`/`avg_size`/`max_size` bound how small/large a chunk may grow.
+    ContentDefined {
+        /// No boundary is considered before a chunk reaches this many bytes.
+        min_size: u32,
+        /// The average chunk size the mask widths are tuned to produce.
+        avg_size: u32,
+        /// A boundary is forced if a chunk reaches this many bytes without one occurring
+        /// naturally.
+        max_size: u32,
+    },
+}
+
+impl Default for ChunkingStrategy {
+    fn default() -> Self {
+        ChunkingStrategy::FixedSize
+    }
+}
+
+impl ChunkingStrategy {
+    /// A content-defined strategy using this crate's default min/avg/max chunk sizes.
+    pub fn content_defined() -> Self {
+        ChunkingStrategy::ContentDefined {
+            min_size: MIN_CHUNK_SIZE,
+            avg_size: MAX_CHUNK_SIZE / 4,
+            max_size: MAX_CHUNK_SIZE,
+        }
+    }
+
+    /// Splits `content` into chunk lengths according to this strategy.
+    pub(crate) fn chunk_lengths(&self, content: &[u8]) -> Vec<u64> {
+        match *self {
+            ChunkingStrategy::FixedSize => fixed_size_lengths(content.len() as u64),
+            ChunkingStrategy::ContentDefined {
+                min_size,
+                avg_size,
+                max_size,
+            } => content_defined_lengths(content, min_size as usize, avg_size, max_size as usize),
+        }
+    }
+}
+
+/// Splits `source_size` bytes into a set of roughly-equal fixed-size chunk lengths, with a
+/// minimum of three chunks so each one has two neighbours to derive its encryption pad from.
+fn fixed_size_lengths(source_size: u64) -> Vec<u64> {
+    if source_size == 0 {
+        return Vec::new();
+    }
+    let num_chunks = usize::max(3, (source_size / u64::from(MAX_CHUNK_SIZE) + 1) as usize);
+    let base_len = source_size / num_chunks as u64;
+    let remainder = source_size % num_chunks as u64;
+    (0..num_chunks)
+        .map(|i| base_len + if (i as u64) < remainder { 1 } else { 0 })
+        .collect()
+}
+
+/// A mask width tuned so that, with a uniformly-distributed gear fingerprint, a boundary occurs
+/// on average every `avg_size` bytes.
+fn mask_for_avg_size(avg_size: u32) -> u64 {
+    let bits = (avg_size.max(1) as f64).log2().round() as u32;
+    if bits == 0 {
+        0
+    } else {
+        (1u64 << bits) - 1
+    }
+}
+
+/// Finds content-defined chunk boundaries using a gear rolling hash: the fingerprint `h` is
+/// updated per byte as `h = (h << 1) + GEAR[byte]`, and a boundary falls wherever `h & mask ==
+/// 0`. No boundary is considered before `min_size`; from there until `2 * min_size` a mask with
+/// one more bit set is used (making an early cut less likely), then the `avg_size` mask, and a
+/// cut is forced at `max_size` regardless.
+fn content_defined_lengths(content: &[u8], min_size: usize, avg_size: u32, max_size: usize) -> Vec<u64> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+
+    let mask = mask_for_avg_size(avg_size);
+    let small_mask = mask << 1 | 1;
+
+    let mut lengths = Vec::new();
+    let mut chunk_start = 0usize;
+    let mut fingerprint: u64 = 0;
+
+    for (i, &byte) in content.iter().enumerate() {
+        fingerprint = (fingerprint << 1).wrapping_add(GEAR[byte as usize]);
+        let chunk_len = i - chunk_start + 1;
+
+        if chunk_len >= max_size {
+            lengths.push(chunk_len as u64);
+            chunk_start = i + 1;
+            fingerprint = 0;
+            continue;
+        }
+        if chunk_len < min_size {
+            continue;
+        }
+        let active_mask = if chunk_len < 2 * min_size { small_mask } else { mask };
+        if fingerprint & active_mask == 0 {
+            lengths.push(chunk_len as u64);
+            chunk_start = i + 1;
+            fingerprint = 0;
+        }
+    }
+
+    if chunk_start < content.len() {
+        lengths.push((content.len() - chunk_start) as u64);
+    }
+
+    // Content-defined chunking needs at least three chunks so every chunk has two neighbours to
+    // derive its pad from; fall back to fixed-size splitting for inputs too small for that.
+    if lengths.len() < 3 {
+        return fixed_size_lengths(content.len() as u64);
+    }
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_fixed_size_when_too_small_for_three_natural_cuts() {
+        let content = vec![0u8; 10];
+        // min_size no smaller than the content itself means no natural cut can occur before EOF.
+        let lengths = content_defined_lengths(&content, content.len(), 1024, 1_000_000);
+        assert_eq!(lengths.len(), 3);
+        assert_eq!(lengths.iter().sum::<u64>(), content.len() as u64);
+    }
+
+    #[test]
+    fn forces_a_cut_at_max_size() {
+        let max_size = 64usize;
+        let content = vec![42u8; max_size * 5];
+        // A mask this wide makes a natural gear-hash boundary exceedingly unlikely, so every cut
+        // observed here is the one `content_defined_lengths` forces at `max_size`.
+        let lengths = content_defined_lengths(&content, 8, u32::MAX, max_size);
+        assert!(lengths.len() >= 3);
+        assert_eq!(lengths.iter().sum::<u64>(), content.len() as u64);
+        for length in &lengths {
+            assert!(*length as usize <= max_size, "chunk exceeded max_size: {}", length);
+        }
+    }
+
+    #[test]
+    fn chunk_defined_lengths_always_cover_the_whole_input() {
+        let content: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let lengths = ChunkingStrategy::content_defined().chunk_lengths(&content);
+        assert!(lengths.len() >= 3);
+        assert_eq!(lengths.iter().sum::<u64>(), content.len() as u64);
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! How chunks are hashed and how the 3-chunk encryption window transforms them.
+//!
+//! A [`Cipher`] is chosen when a `SelfEncryptor` is constructed and its [`CipherSuite`] is
+//! recorded in the resulting `DataMap`, so [`cipher_for`] can hand back a matching
+//! implementation on read without the caller needing to remember which one they used. This lets
+//! the crate move off SHA3-256 chunk hashes / the original XOR transform for new data without
+//! breaking the ability to read data maps written under the old one. The address a chunk is
+//! stored under is not chosen by the `Cipher`: it comes from `Storage::generate_address`.
+
+use serde::{Deserialize, Serialize};
+use tiny_keccak::sha3_256;
+
+/// A zero-filled stand-in for the hash of a neighbour that doesn't exist, used for the first
+/// chunk's previous neighbour and the last chunk's next neighbour.
+pub(crate) const SENTINEL_PRE_HASH: [u8; 32] = [0u8; 32];
+
+/// Identifies which [`Cipher`] implementation produced a `DataMap`'s chunks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum CipherSuite {
+    /// SHA3-256 chunk hashes with an XOR keystream derived from neighbouring chunks' hashes. The
+    /// only suite this crate supported before `Cipher` was introduced, and still the default.
+    Sha3Xor,
+    /// BLAKE3 chunk hashes with a ChaCha20 keystream derived from neighbouring chunks' hashes.
+    Blake3ChaCha20,
+}
+
+impl Default for CipherSuite {
+    fn default() -> Self {
+        CipherSuite::Sha3Xor
+    }
+}
+
+/// Chooses how chunks are hashed and how the 3-chunk encryption window transforms them.
+pub trait Cipher: Send + Sync {
+    /// Which suite this is, for recording in the `DataMap`.
+    fn suite(&self) -> CipherSuite;
+
+    /// Hashes a chunk's plaintext. The result is recorded as the chunk's `pre_hash` and is the
+    /// value its neighbours derive their keystream from; it is not the chunk's storage address,
+    /// which `Storage::generate_address` produces from the encrypted chunk.
+    fn hash(&self, chunk: &[u8]) -> Vec<u8>;
+
+    /// Transforms `data` in place using a keystream derived from `index` and the neighbouring
+    /// chunks' hashes. Must be its own inverse, since it is used for both encryption and
+    /// decryption.
+    fn transform(&self, data: &mut [u8], index: usize, prev_hash: &[u8], next_hash: &[u8]);
+}
+
+/// Returns the `Cipher` implementation matching `suite`, for encoding new chunks or decoding
+/// chunks from a `DataMap` recorded under that suite.
+pub fn cipher_for(suite: CipherSuite) -> Box<dyn Cipher> {
+    match suite {
+        CipherSuite::Sha3Xor => Box::new(Sha3XorCipher),
+        CipherSuite::Blake3ChaCha20 => Box::new(Blake3ChaCha20Cipher),
+    }
+}
+
+/// The original cipher: SHA3-256 chunk hashes, XORed with a SHA3-256-derived keystream.
+#[derive(Default)]
+pub struct Sha3XorCipher;
+
+impl Cipher for Sha3XorCipher {
+    fn suite(&self) -> CipherSuite {
+        CipherSuite::Sha3Xor
+    }
+
+    fn hash(&self, chunk: &[u8]) -> Vec<u8> {
+        sha3_256(chunk).to_vec()
+    }
+
+    fn transform(&self, data: &mut [u8], index: usize, prev_hash: &[u8], next_hash: &[u8]) {
+        let mut seed = Vec::with_capacity(prev_hash.len() + next_hash.len() + 8);
+        seed.extend_from_slice(prev_hash);
+        seed.extend_from_slice(next_hash);
+        seed.extend_from_slice(&index.to_le_bytes());
+
+        let mut pad = sha3_256(&seed).to_vec();
+        for (offset, byte) in data.iter_mut().enumerate() {
+            if offset > 0 && offset % pad.len() == 0 {
+                pad = sha3_256(&pad).to_vec();
+            }
+            *byte ^= pad[offset % pad.len()];
+        }
+    }
+}
+
+/// An alternative cipher using BLAKE3 chunk hashes and a ChaCha20 keystream, for callers migrating
+/// off SHA3/the original XOR step, or operating where a specific cipher suite is mandated.
+#[derive(Default)]
+pub struct Blake3ChaCha20Cipher;
+
+impl Cipher for Blake3ChaCha20Cipher {
+    fn suite(&self) -> CipherSuite {
+        CipherSuite::Blake3ChaCha20
+    }
+
+    fn hash(&self, chunk: &[u8]) -> Vec<u8> {
+        blake3::hash(chunk).as_bytes().to_vec()
+    }
+
+    fn transform(&self, data: &mut [u8], index: usize, prev_hash: &[u8], next_hash: &[u8]) {
+        use chacha20::cipher::{KeyIvInit, StreamCipher};
+        use chacha20::ChaCha20;
+
+        let mut seed = Vec::with_capacity(prev_hash.len() + next_hash.len() + 8);
+        seed.extend_from_slice(prev_hash);
+        seed.extend_from_slice(next_hash);
+        seed.extend_from_slice(&index.to_le_bytes());
+        let key = *blake3::hash(&seed).as_bytes();
+
+        let nonce = [0u8; 12];
+        let mut chacha = ChaCha20::new(&key.into(), &nonce.into());
+        chacha.apply_keystream(data);
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::cipher::CipherSuite;
+use serde::{Deserialize, Serialize};
+
+/// Metadata describing a single encrypted chunk, as recorded in a `DataMap`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    /// Position of this chunk within the original file.
+    pub index: usize,
+    /// Address the encrypted chunk is stored under in `Storage`.
+    pub hash: Vec<u8>,
+    /// Hash of the chunk's plaintext, used to derive the encryption pad for its neighbours.
+    pub pre_hash: Vec<u8>,
+    /// Length in bytes of the chunk's plaintext.
+    pub source_size: u64,
+}
+
+/// Describes how to reassemble a file from chunks stored in a `Storage` backend.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DataMap {
+    /// The file was split into encrypted chunks, listed here in order, together with the
+    /// `CipherSuite` they were addressed and encrypted with.
+    Chunks {
+        /// The chunks, in file order.
+        chunks: Vec<ChunkInfo>,
+        /// Which `Cipher` implementation to use when reading these chunks back.
+        cipher_suite: CipherSuite,
+    },
+    /// The file was small enough to be stored inline rather than chunked.
+    Content(Vec<u8>),
+    /// No data has been written yet.
+    None,
+}
+
+impl DataMap {
+    /// Returns the total length in bytes of the file this `DataMap` describes.
+    pub fn len(&self) -> u64 {
+        match *self {
+            DataMap::Chunks { ref chunks, .. } => chunks.iter().map(|c| c.source_size).sum(),
+            DataMap::Content(ref content) => content.len() as u64,
+            DataMap::None => 0,
+        }
+    }
+
+    /// Returns `true` if this `DataMap` describes an empty file.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the chunks (addresses, pre-hashes and sizes) recorded in this `DataMap`, if any.
+    pub fn chunks(&self) -> Vec<ChunkInfo> {
+        match *self {
+            DataMap::Chunks { ref chunks, .. } => chunks.clone(),
+            DataMap::Content(_) | DataMap::None => Vec::new(),
+        }
+    }
+
+    /// Returns the `CipherSuite` these chunks were encoded with, or the default suite for
+    /// non-chunked `DataMap`s (which have no cipher-dependent content to read back).
+    pub fn cipher_suite(&self) -> CipherSuite {
+        match *self {
+            DataMap::Chunks { cipher_suite, .. } => cipher_suite,
+            DataMap::Content(_) | DataMap::None => CipherSuite::default(),
+        }
+    }
+}
@@ -0,0 +1,669 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! A FUSE filesystem backed by [`SelfEncryptor`](crate::SelfEncryptor).
+//!
+//! Each regular file in the mount is represented by a [`DataMap`] held in an in-memory inode
+//! catalog; `read`/`write` on an open file handle translate directly into
+//! `SelfEncryptor::read`/`SelfEncryptor::write` against the backing [`Storage`]. The first read
+//! or write on a handle fetches and decrypts all of the file's chunks, and later ones on that
+//! handle are served from memory. A fresh `DataMap` is flushed on the final `release` of a
+//! file that was opened for writing. `readdir` lists the in-memory catalog directly, and
+//! `create`/`mknod`/`mkdir` populate it, `unlink`/`rmdir` remove from it, and `rename` moves
+//! entries between directories, so the mount is a real directory tree browsable and writable
+//! with ordinary tools (`ls`, `touch`, `mkdir`, `mv`, `rm`, `cp`) rather than only through
+//! `SelfEncryptingFs::create_file`.
+//!
+//! This module requires the `fuse` feature and is only usable on platforms FUSE supports
+//! (Linux and macOS).
+
+use crate::{DataMap, SelfEncryptor, Storage};
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyWrite, Request, TimeOrNow,
+};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TTL: Duration = Duration::from_secs(1);
+const ROOT_INODE: u64 = 1;
+
+/// A single entry in the mount's inode catalog.
+struct Inode {
+    parent: u64,
+    name: String,
+    kind: FileType,
+    /// `DataMap` for the most recently closed version of this file. `None` for directories and
+    /// for files that have never been written.
+    data_map: DataMap,
+    children: Vec<u64>,
+}
+
+/// Tracks a `SelfEncryptor` constructed for the lifetime of one `open`/`release` pair.
+struct OpenFile<S: Storage> {
+    inode: u64,
+    encryptor: SelfEncryptor<S>,
+    dirty: bool,
+}
+
+/// A FUSE filesystem that transparently self-encrypts every regular file it contains.
+///
+/// `storage_for` is called once per `open`, handing each file handle its own `Storage` instance
+/// (e.g. a fresh handle onto a shared disk directory) so encryptors can be constructed and
+/// flushed independently.
+pub struct SelfEncryptingFs<S: Storage, F: Fn() -> S> {
+    inodes: HashMap<u64, Inode>,
+    next_inode: u64,
+    next_fh: u64,
+    open_files: HashMap<u64, OpenFile<S>>,
+    storage_for: F,
+}
+
+impl<S: Storage + 'static, F: Fn() -> S> SelfEncryptingFs<S, F> {
+    /// Creates an empty mount with a single root directory.
+    pub fn new(storage_for: F) -> Self {
+        let mut inodes = HashMap::new();
+        inodes.insert(
+            ROOT_INODE,
+            Inode {
+                parent: ROOT_INODE,
+                name: String::new(),
+                kind: FileType::Directory,
+                data_map: DataMap::None,
+                children: Vec::new(),
+            },
+        );
+        SelfEncryptingFs {
+            inodes,
+            next_inode: ROOT_INODE + 1,
+            next_fh: 1,
+            open_files: HashMap::new(),
+            storage_for,
+        }
+    }
+
+    fn attr_for(&self, inode: u64, entry: &Inode) -> FileAttr {
+        let size = entry.data_map.len();
+        FileAttr {
+            ino: inode,
+            size,
+            blocks: (size + 511) / 512,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: entry.kind,
+            perm: if entry.kind == FileType::Directory {
+                0o755
+            } else {
+                0o644
+            },
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    fn lookup_child(&self, parent: u64, name: &str) -> Option<u64> {
+        let parent_inode = self.inodes.get(&parent)?;
+        parent_inode
+            .children
+            .iter()
+            .find(|child| self.inodes.get(child).map(|i| i.name.as_str()) == Some(name))
+            .copied()
+    }
+
+    /// Creates a new, empty regular file under `parent`, returning its inode number.
+    pub fn create_file(&mut self, parent: u64, name: &str) -> u64 {
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(
+            inode,
+            Inode {
+                parent,
+                name: name.to_owned(),
+                kind: FileType::RegularFile,
+                data_map: DataMap::None,
+                children: Vec::new(),
+            },
+        );
+        if let Some(dir) = self.inodes.get_mut(&parent) {
+            dir.children.push(inode);
+        }
+        inode
+    }
+
+    /// Creates a new, empty subdirectory under `parent`, returning its inode number.
+    fn create_dir(&mut self, parent: u64, name: &str) -> u64 {
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(
+            inode,
+            Inode {
+                parent,
+                name: name.to_owned(),
+                kind: FileType::Directory,
+                data_map: DataMap::None,
+                children: Vec::new(),
+            },
+        );
+        if let Some(dir) = self.inodes.get_mut(&parent) {
+            dir.children.push(inode);
+        }
+        inode
+    }
+
+    /// Detaches `inode` from its parent's child list without touching the catalog entry itself.
+    fn detach(&mut self, inode: u64) {
+        if let Some(entry) = self.inodes.get(&inode) {
+            let parent = entry.parent;
+            if let Some(dir) = self.inodes.get_mut(&parent) {
+                dir.children.retain(|&child| child != inode);
+            }
+        }
+    }
+}
+
+impl<S: Storage + Send + 'static, F: Fn() -> S + Send + 'static> Filesystem
+    for SelfEncryptingFs<S, F>
+{
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        match self.lookup_child(parent, name) {
+            Some(inode) => {
+                let entry = &self.inodes[&inode];
+                reply.entry(&TTL, &self.attr_for(inode, entry), 0);
+            }
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, inode: u64, reply: ReplyAttr) {
+        match self.inodes.get(&inode) {
+            Some(entry) => reply.attr(&TTL, &self.attr_for(inode, entry)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        inode: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let entry = match self.inodes.get(&inode) {
+            Some(entry) => entry,
+            None => return reply.error(libc::ENOENT),
+        };
+        if entry.kind != FileType::Directory {
+            return reply.error(libc::ENOTDIR);
+        }
+
+        let mut listing = vec![
+            (inode, FileType::Directory, ".".to_owned()),
+            (entry.parent, FileType::Directory, "..".to_owned()),
+        ];
+        for &child in &entry.children {
+            if let Some(child_entry) = self.inodes.get(&child) {
+                listing.push((child, child_entry.kind, child_entry.name.clone()));
+            }
+        }
+
+        for (position, (child_inode, kind, name)) in
+            listing.into_iter().enumerate().skip(offset as usize)
+        {
+            // `add` returns `true` once the reply buffer is full; stop feeding it entries and
+            // let the kernel ask again with a later `offset`.
+            if reply.add(child_inode, (position + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn mknod(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        if self.inodes.get(&parent).is_none() {
+            return reply.error(libc::ENOENT);
+        }
+        if self.lookup_child(parent, name).is_some() {
+            return reply.error(libc::EEXIST);
+        }
+        if mode & libc::S_IFMT != libc::S_IFREG {
+            return reply.error(libc::EPERM);
+        }
+
+        let inode = self.create_file(parent, name);
+        let entry = &self.inodes[&inode];
+        reply.entry(&TTL, &self.attr_for(inode, entry), 0);
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        if self.inodes.get(&parent).is_none() {
+            return reply.error(libc::ENOENT);
+        }
+        if self.lookup_child(parent, name).is_some() {
+            return reply.error(libc::EEXIST);
+        }
+
+        let inode = self.create_dir(parent, name);
+        let entry = &self.inodes[&inode];
+        reply.entry(&TTL, &self.attr_for(inode, entry), 0);
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        let inode = match self.lookup_child(parent, name) {
+            Some(inode) => inode,
+            None => return reply.error(libc::ENOENT),
+        };
+        if self.inodes[&inode].kind != FileType::RegularFile {
+            return reply.error(libc::EISDIR);
+        }
+
+        self.detach(inode);
+        self.inodes.remove(&inode);
+        reply.ok();
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        let inode = match self.lookup_child(parent, name) {
+            Some(inode) => inode,
+            None => return reply.error(libc::ENOENT),
+        };
+        let entry = &self.inodes[&inode];
+        if entry.kind != FileType::Directory {
+            return reply.error(libc::ENOTDIR);
+        }
+        if !entry.children.is_empty() {
+            return reply.error(libc::ENOTEMPTY);
+        }
+
+        self.detach(inode);
+        self.inodes.remove(&inode);
+        reply.ok();
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        let new_name = match new_name.to_str() {
+            Some(new_name) => new_name,
+            None => return reply.error(libc::EINVAL),
+        };
+        let inode = match self.lookup_child(parent, name) {
+            Some(inode) => inode,
+            None => return reply.error(libc::ENOENT),
+        };
+        if self.inodes.get(&new_parent).is_none() {
+            return reply.error(libc::ENOENT);
+        }
+
+        if let Some(existing) = self.lookup_child(new_parent, new_name) {
+            if existing == inode {
+                return reply.ok();
+            }
+            if self.inodes[&existing].kind == FileType::Directory
+                && !self.inodes[&existing].children.is_empty()
+            {
+                return reply.error(libc::ENOTEMPTY);
+            }
+            self.detach(existing);
+            self.inodes.remove(&existing);
+        }
+
+        self.detach(inode);
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            entry.parent = new_parent;
+            entry.name = new_name.to_owned();
+        }
+        if let Some(dir) = self.inodes.get_mut(&new_parent) {
+            dir.children.push(inode);
+        }
+        reply.ok();
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        inode: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let entry = match self.inodes.get_mut(&inode) {
+            Some(entry) => entry,
+            None => return reply.error(libc::ENOENT),
+        };
+        // Only truncation to empty is supported, since there's no open `SelfEncryptor` here to
+        // truncate through; a non-empty resize would need a real byte-level truncate on the
+        // encryptor, which doesn't exist yet.
+        if size == Some(0) {
+            entry.data_map = DataMap::None;
+        }
+        reply.attr(&TTL, &self.attr_for(inode, entry));
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        if self.inodes.get(&parent).is_none() {
+            return reply.error(libc::ENOENT);
+        }
+        if self.lookup_child(parent, name).is_some() {
+            return reply.error(libc::EEXIST);
+        }
+
+        let inode = self.create_file(parent, name);
+        let storage = (self.storage_for)();
+        match SelfEncryptor::new(storage, DataMap::None) {
+            Ok(encryptor) => {
+                let fh = self.next_fh;
+                self.next_fh += 1;
+                self.open_files.insert(
+                    fh,
+                    OpenFile {
+                        inode,
+                        encryptor,
+                        dirty: false,
+                    },
+                );
+                let entry = &self.inodes[&inode];
+                reply.created(&TTL, &self.attr_for(inode, entry), 0, fh, 0);
+            }
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn open(&mut self, _req: &Request, inode: u64, _flags: i32, reply: fuser::ReplyOpen) {
+        let entry = match self.inodes.get(&inode) {
+            Some(entry) => entry,
+            None => return reply.error(libc::ENOENT),
+        };
+        let storage = (self.storage_for)();
+        match SelfEncryptor::new(storage, entry.data_map.clone()) {
+            Ok(encryptor) => {
+                let fh = self.next_fh;
+                self.next_fh += 1;
+                self.open_files.insert(
+                    fh,
+                    OpenFile {
+                        inode,
+                        encryptor,
+                        dirty: false,
+                    },
+                );
+                reply.opened(fh, 0);
+            }
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let open_file = match self.open_files.get(&fh) {
+            Some(open_file) => open_file,
+            None => return reply.error(libc::EBADF),
+        };
+        // Blocks on the async encryptor: the first access on this handle fetches and decrypts
+        // every chunk of the file, later reads are served from memory.
+        match futures::executor::block_on(open_file.encryptor.read(offset as usize, size as usize))
+        {
+            Ok(data) => reply.data(&data),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let open_file = match self.open_files.get_mut(&fh) {
+            Some(open_file) => open_file,
+            None => return reply.error(libc::EBADF),
+        };
+        match futures::executor::block_on(open_file.encryptor.write(data, offset as usize)) {
+            Ok(()) => {
+                open_file.dirty = true;
+                reply.written(data.len() as u32);
+            }
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let open_file = match self.open_files.remove(&fh) {
+            Some(open_file) => open_file,
+            None => return reply.error(libc::EBADF),
+        };
+        if !open_file.dirty {
+            return reply.ok();
+        }
+        match futures::executor::block_on(open_file.encryptor.close()) {
+            Ok((data_map, _storage)) => {
+                if let Some(entry) = self.inodes.get_mut(&open_file.inode) {
+                    entry.data_map = data_map;
+                }
+                reply.ok();
+            }
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+}
+
+// `fuser`'s `Reply*` types have no public constructor outside of a live FUSE session, so the
+// `Filesystem` trait methods themselves can't be driven directly in a unit test. These tests
+// instead cover `SelfEncryptingFs`'s inode catalog (the glue `lookup`/`readdir`/`create`/`mkdir`/
+// `unlink`/`rmdir`/`rename` all sit on top of) and the `SelfEncryptor` round trip `open`/`read`/
+// `write`/`release` perform against it, using the same kind of mock `Storage` the rest of the
+// crate's test suites use.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap as StdHashMap;
+
+    #[derive(Debug)]
+    struct MockStorageError;
+
+    impl std::fmt::Display for MockStorageError {
+        fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(formatter, "mock storage error")
+        }
+    }
+
+    impl std::error::Error for MockStorageError {}
+    impl crate::storage::StorageError for MockStorageError {}
+
+    #[derive(Default)]
+    struct MockStorage {
+        chunks: StdHashMap<Vec<u8>, Vec<u8>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for MockStorage {
+        type Error = MockStorageError;
+
+        async fn get(&mut self, name: &[u8]) -> Result<Vec<u8>, Self::Error> {
+            self.chunks.get(name).cloned().ok_or(MockStorageError)
+        }
+
+        async fn put(&mut self, name: Vec<u8>, data: Vec<u8>) -> Result<(), Self::Error> {
+            let _ = self.chunks.insert(name, data);
+            Ok(())
+        }
+
+        async fn generate_address(&self, data: &[u8]) -> Vec<u8> {
+            tiny_keccak::sha3_256(data).to_vec()
+        }
+    }
+
+    fn test_fs() -> SelfEncryptingFs<MockStorage, fn() -> MockStorage> {
+        SelfEncryptingFs::new(MockStorage::default)
+    }
+
+    #[test]
+    fn new_mount_has_an_empty_root_directory() {
+        let fs = test_fs();
+        let root = &fs.inodes[&ROOT_INODE];
+        assert_eq!(root.kind, FileType::Directory);
+        assert!(root.children.is_empty());
+    }
+
+    #[test]
+    fn create_file_and_create_dir_register_children_findable_via_lookup_child() {
+        let mut fs = test_fs();
+        let file = fs.create_file(ROOT_INODE, "a.txt");
+        let dir = fs.create_dir(ROOT_INODE, "sub");
+
+        assert_eq!(fs.lookup_child(ROOT_INODE, "a.txt"), Some(file));
+        assert_eq!(fs.lookup_child(ROOT_INODE, "sub"), Some(dir));
+        assert_eq!(fs.inodes[&file].kind, FileType::RegularFile);
+        assert_eq!(fs.inodes[&dir].kind, FileType::Directory);
+        assert_eq!(fs.inodes[&ROOT_INODE].children, vec![file, dir]);
+    }
+
+    #[test]
+    fn attr_for_reports_kind_and_the_data_maps_length() {
+        let mut fs = test_fs();
+        let file = fs.create_file(ROOT_INODE, "a.txt");
+        fs.inodes.get_mut(&file).unwrap().data_map = DataMap::Content(vec![1, 2, 3]);
+
+        let attr = fs.attr_for(file, &fs.inodes[&file]);
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(attr.size, 3);
+
+        let root_attr = fs.attr_for(ROOT_INODE, &fs.inodes[&ROOT_INODE]);
+        assert_eq!(root_attr.kind, FileType::Directory);
+    }
+
+    #[test]
+    fn detach_removes_a_child_from_its_parent_without_deleting_the_inode() {
+        let mut fs = test_fs();
+        let file = fs.create_file(ROOT_INODE, "a.txt");
+
+        fs.detach(file);
+
+        assert!(fs.inodes[&ROOT_INODE].children.is_empty());
+        assert!(fs.inodes.contains_key(&file));
+        assert_eq!(fs.lookup_child(ROOT_INODE, "a.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn a_files_contents_round_trip_through_the_same_encryptor_flow_as_write_and_read() {
+        // Mirrors what `write` + `release` + `open` + `read` do against an inode's `DataMap`,
+        // without needing a live FUSE session to drive the `Filesystem` trait methods themselves.
+        let content = vec![42u8; 4096];
+
+        let encryptor = SelfEncryptor::new(MockStorage::default(), DataMap::None).unwrap();
+        encryptor.write(&content, 0).await.unwrap();
+        let (data_map, storage) = encryptor.close().await.unwrap();
+
+        let reopened = SelfEncryptor::new(storage, data_map).unwrap();
+        let read_back = reopened.read(0, content.len()).await.unwrap();
+        assert_eq!(read_back, content);
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Self-encrypting files (convergent encryption plus obfuscation).
+//!
+//! This library provides the [`SelfEncryptor`] type, which is responsible for converting a
+//! stream of bytes into a set of encrypted chunks plus a small [`DataMap`] describing how to
+//! reassemble them, and back again. Chunks are addressed by content hash via the pluggable
+//! [`Storage`] trait, so callers can back a `SelfEncryptor` with disk, a database, or a
+//! distributed store.
+
+// For explanation of lint checks, run `rustc -W help` or see
+// https://github.com/maidsafe/QA/blob/master/Documentation/Rust%20Lint%20Checks.md
+#![forbid(
+    arithmetic_overflow,
+    mutable_transmutes,
+    no_mangle_const_items,
+    unknown_crate_types,
+    warnings
+)]
+#![deny(
+    bad_style,
+    deprecated,
+    improper_ctypes,
+    missing_docs,
+    non_shorthand_field_patterns,
+    overflowing_literals,
+    stable_features,
+    unconditional_recursion,
+    unknown_lints,
+    unsafe_code,
+    unused,
+    unused_allocation,
+    unused_attributes,
+    unused_comparisons,
+    unused_features,
+    unused_parens,
+    while_true
+)]
+#![warn(
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results
+)]
+#![allow(
+    box_pointers,
+    missing_copy_implementations,
+    missing_debug_implementations,
+    variant_size_differences
+)]
+
+mod chunking;
+pub mod cipher;
+mod data_map;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+mod self_encryptor;
+mod sequencer;
+mod storage;
+mod streaming;
+pub mod trust;
+
+pub mod test_helpers;
+
+pub use crate::chunking::ChunkingStrategy;
+pub use crate::cipher::{Cipher, CipherSuite};
+pub use crate::data_map::{ChunkInfo, DataMap};
+pub use crate::self_encryptor::{SelfEncryptor, SelfEncryptorOptions};
+pub use crate::storage::{Storage, StorageError};
+pub use crate::streaming::{
+    read_stream, write_stream, write_stream_with_cipher_suite, StreamingError,
+};
+
+/// A size limit for the in-memory `write`/`close` API of `SelfEncryptor`.
+///
+/// `SelfEncryptor` does not enforce it: `write` and `close` accept data of any size and hold all
+/// of it in memory. Callers needing to process larger files should use a streaming API instead
+/// of buffering the whole file in memory.
+pub const MAX_FILE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Files smaller than three times this size are stored directly in the `DataMap` rather than
+/// being split and encrypted, since there would be no neighbouring chunks to derive keys from.
+pub const MIN_CHUNK_SIZE: u32 = 1024;
+
+/// The largest size a single chunk will grow to under the default fixed-size chunking scheme.
+pub const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
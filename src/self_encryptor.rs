@@ -0,0 +1,409 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::chunking::ChunkingStrategy;
+use crate::cipher::{cipher_for, Cipher, CipherSuite, SENTINEL_PRE_HASH};
+use crate::data_map::{ChunkInfo, DataMap};
+use crate::sequencer::Sequencer;
+use crate::storage::Storage;
+use std::collections::HashSet;
+use tokio::sync::Mutex;
+
+struct Inner<S> {
+    storage: S,
+    sequencer: Sequencer,
+    loaded: bool,
+}
+
+/// Splits a file into self-encrypted chunks and reassembles it again.
+///
+/// A `SelfEncryptor` wraps a `Storage` backend and an existing (or empty) `DataMap`. Callers
+/// `write` plaintext at arbitrary offsets, then `close` to flush the result as encrypted chunks
+/// to `Storage` and obtain the resulting `DataMap`. `read` does the reverse: the first access
+/// fetches and decrypts every chunk of the existing `DataMap` into memory, and reads are then
+/// served from that buffer; a read does not fetch only the chunks it covers.
+pub struct SelfEncryptor<S: Storage> {
+    inner: Mutex<Inner<S>>,
+    original_map: DataMap,
+    chunking_strategy: ChunkingStrategy,
+    reference_map: Option<DataMap>,
+    cipher: Box<dyn Cipher>,
+}
+
+impl<S: Storage> SelfEncryptor<S> {
+    /// Creates a new `SelfEncryptor` over `storage`, seeding its buffer from `data_map` if it
+    /// describes an existing file. Chunked data maps are fetched and decrypted lazily, on first
+    /// `read`, `write` or `close`; `len` and `is_empty` answer from the `DataMap` until then.
+    /// Chunks are re-split on fixed-size boundaries on `close`; use
+    /// `new_with_chunking_strategy` to pick content-defined chunking instead.
+    pub fn new(storage: S, data_map: DataMap) -> Result<Self, S::Error> {
+        Self::new_with_chunking_strategy(storage, data_map, ChunkingStrategy::default())
+    }
+
+    /// Creates a new `SelfEncryptor`, as `new`, but splitting the file on `close` according to
+    /// `chunking_strategy` rather than the default fixed-size scheme.
+    pub fn new_with_chunking_strategy(
+        storage: S,
+        data_map: DataMap,
+        chunking_strategy: ChunkingStrategy,
+    ) -> Result<Self, S::Error> {
+        SelfEncryptorOptions::new(chunking_strategy).build(storage, data_map)
+    }
+
+    /// Creates a new `SelfEncryptor`, as `new`, additionally comparing chunks against
+    /// `reference_map` on `close`: a chunk whose address already appears in `reference_map`, or
+    /// already exists in `storage`, is not re-sent via `Storage::put`. This lets editing one
+    /// region of a large file re-upload only the chunks that actually changed, by passing the
+    /// `DataMap` of the previous version as `reference_map`.
+    pub fn new_with_reference(
+        storage: S,
+        data_map: DataMap,
+        reference_map: DataMap,
+    ) -> Result<Self, S::Error> {
+        SelfEncryptorOptions::default()
+            .reference_map(reference_map)
+            .build(storage, data_map)
+    }
+
+    /// Creates a new `SelfEncryptor`, as `new`, using `cipher_suite` to address and encrypt any
+    /// chunks written via `close`. Ignored if `data_map` already describes chunks: those were
+    /// addressed and encrypted under whichever suite produced them, recorded in the `DataMap`
+    /// itself, and must be read back with the matching `Cipher` regardless of what's requested
+    /// here.
+    pub fn new_with_cipher_suite(
+        storage: S,
+        data_map: DataMap,
+        cipher_suite: CipherSuite,
+    ) -> Result<Self, S::Error> {
+        SelfEncryptorOptions::default()
+            .cipher_suite(cipher_suite)
+            .build(storage, data_map)
+    }
+
+    fn new_with_options(
+        storage: S,
+        data_map: DataMap,
+        chunking_strategy: ChunkingStrategy,
+        reference_map: Option<DataMap>,
+        cipher_suite: CipherSuite,
+    ) -> Result<Self, S::Error> {
+        let (sequencer, loaded) = match &data_map {
+            DataMap::Content(content) => (Sequencer::new_with_data(content.clone()), true),
+            DataMap::Chunks { .. } => (Sequencer::default(), false),
+            DataMap::None => (Sequencer::default(), true),
+        };
+        let cipher_suite = match &data_map {
+            DataMap::Chunks { .. } => data_map.cipher_suite(),
+            DataMap::Content(_) | DataMap::None => cipher_suite,
+        };
+        Ok(SelfEncryptor {
+            inner: Mutex::new(Inner {
+                storage,
+                sequencer,
+                loaded,
+            }),
+            original_map: data_map,
+            chunking_strategy,
+            reference_map,
+            cipher: cipher_for(cipher_suite),
+        })
+    }
+
+    /// Fetches and decrypts every chunk of `self.original_map` into the sequencer, if that has
+    /// not already happened.
+    async fn ensure_loaded(&self, inner: &mut Inner<S>) -> Result<(), S::Error> {
+        if inner.loaded {
+            return Ok(());
+        }
+        if let DataMap::Chunks { chunks, .. } = self.original_map.clone() {
+            let mut content = Vec::with_capacity(chunks.len());
+            for chunk in &chunks {
+                let encrypted = inner.storage.get(&chunk.hash).await?;
+                content.push(encrypted);
+            }
+            let plaintext = decrypt_chunks(self.cipher.as_ref(), &chunks, content);
+            inner.sequencer = Sequencer::new_with_data(plaintext);
+        }
+        inner.loaded = true;
+        Ok(())
+    }
+
+    /// Writes `data` into the encryptor's buffer at `offset`, extending the file if needed.
+    pub async fn write(&self, data: &[u8], offset: usize) -> Result<(), S::Error> {
+        let mut inner = self.inner.lock().await;
+        self.ensure_loaded(&mut inner).await?;
+        inner.sequencer.write(data, offset);
+        Ok(())
+    }
+
+    /// Reads `len` bytes starting at `offset` from the buffer, loading source chunks first if
+    /// necessary.
+    pub async fn read(&self, offset: usize, len: usize) -> Result<Vec<u8>, S::Error> {
+        let mut inner = self.inner.lock().await;
+        self.ensure_loaded(&mut inner).await?;
+        Ok(inner.sequencer.read(offset, len))
+    }
+
+    /// The current length of the file held by this encryptor.
+    pub async fn len(&self) -> u64 {
+        let inner = self.inner.lock().await;
+        if !inner.loaded {
+            return self.original_map.len();
+        }
+        inner.sequencer.len() as u64
+    }
+
+    /// Returns `true` if the encryptor currently holds no data.
+    pub async fn is_empty(&self) -> bool {
+        let inner = self.inner.lock().await;
+        if !inner.loaded {
+            return self.original_map.is_empty();
+        }
+        inner.sequencer.is_empty()
+    }
+
+    /// Flushes the buffered plaintext as encrypted chunks to `Storage`, returning the resulting
+    /// `DataMap` together with the storage backend for reuse.
+    pub async fn close(self) -> Result<(DataMap, S), S::Error> {
+        {
+            let mut inner = self.inner.lock().await;
+            self.ensure_loaded(&mut inner).await?;
+        }
+        let mut inner = self.inner.into_inner();
+        let content = inner.sequencer.into_vec();
+
+        if (content.len() as u64) < u64::from(crate::MIN_CHUNK_SIZE) * 3 {
+            return Ok((DataMap::Content(content), inner.storage));
+        }
+
+        let lengths = self.chunking_strategy.chunk_lengths(&content);
+        let mut chunks = Vec::with_capacity(lengths.len());
+        let mut plaintext_chunks = Vec::with_capacity(lengths.len());
+        let mut start = 0usize;
+        for (index, length) in lengths.iter().enumerate() {
+            let end = start + *length as usize;
+            let plain = content[start..end].to_vec();
+            chunks.push(ChunkInfo {
+                index,
+                hash: Vec::new(),
+                pre_hash: self.cipher.hash(&plain),
+                source_size: *length,
+            });
+            plaintext_chunks.push(plain);
+            start = end;
+        }
+
+        let known_addresses: HashSet<Vec<u8>> = self
+            .reference_map
+            .as_ref()
+            .map(|reference| reference.chunks().into_iter().map(|chunk| chunk.hash).collect())
+            .unwrap_or_default();
+
+        let num_chunks = chunks.len();
+        for index in 0..num_chunks {
+            let prev_pre_hash = neighbour_pre_hash(&chunks, index, -1);
+            let next_pre_hash = neighbour_pre_hash(&chunks, index, 1);
+            let mut encrypted = plaintext_chunks[index].clone();
+            self.cipher
+                .transform(&mut encrypted, index, &prev_pre_hash, &next_pre_hash);
+            let address = inner.storage.generate_address(&encrypted).await;
+
+            // Only probe `Storage::has` when a reference map was actually supplied: its default
+            // implementation costs a full `get`, so callers who never asked for incremental
+            // encryption shouldn't pay for an existence check on every chunk.
+            let already_stored = known_addresses.contains(&address)
+                || (self.reference_map.is_some() && inner.storage.has(&address).await?);
+            if !already_stored {
+                inner.storage.put(address.clone(), encrypted).await?;
+            }
+            chunks[index].hash = address;
+        }
+
+        Ok((
+            DataMap::Chunks {
+                chunks,
+                cipher_suite: self.cipher.suite(),
+            },
+            inner.storage,
+        ))
+    }
+}
+
+/// Composes the options `SelfEncryptor::new*` constructors otherwise only offer pairwise —
+/// chunking strategy, reference map, and cipher suite can each be set independently, then
+/// `build` produces the `SelfEncryptor`. Useful when, say, content-defined chunking needs to be
+/// combined with reference-based incremental encryption.
+///
+/// ```no_run
+/// # use self_encryption::{ChunkingStrategy, CipherSuite, DataMap, SelfEncryptorOptions, Storage};
+/// # async fn example<S: Storage>(storage: S, data_map: DataMap, reference_map: DataMap) -> Result<(), S::Error> {
+/// let se = SelfEncryptorOptions::new(ChunkingStrategy::content_defined())
+///     .reference_map(reference_map)
+///     .cipher_suite(CipherSuite::Blake3ChaCha20)
+///     .build(storage, data_map)?;
+/// # let _ = se;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct SelfEncryptorOptions {
+    chunking_strategy: ChunkingStrategy,
+    reference_map: Option<DataMap>,
+    cipher_suite: CipherSuite,
+}
+
+impl SelfEncryptorOptions {
+    /// Creates a new set of options using `chunking_strategy`, with no reference map and the
+    /// default cipher suite.
+    pub fn new(chunking_strategy: ChunkingStrategy) -> Self {
+        SelfEncryptorOptions {
+            chunking_strategy,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the chunking strategy used to split the file on `close`.
+    pub fn chunking_strategy(mut self, chunking_strategy: ChunkingStrategy) -> Self {
+        self.chunking_strategy = chunking_strategy;
+        self
+    }
+
+    /// Sets a reference `DataMap` to compare chunks against on `close`, as `SelfEncryptor::new_with_reference`.
+    pub fn reference_map(mut self, reference_map: DataMap) -> Self {
+        self.reference_map = Some(reference_map);
+        self
+    }
+
+    /// Sets the cipher suite used to address and encrypt chunks, as `SelfEncryptor::new_with_cipher_suite`.
+    pub fn cipher_suite(mut self, cipher_suite: CipherSuite) -> Self {
+        self.cipher_suite = cipher_suite;
+        self
+    }
+
+    /// Builds the `SelfEncryptor` over `storage` and `data_map` with the options collected so far.
+    pub fn build<S: Storage>(self, storage: S, data_map: DataMap) -> Result<SelfEncryptor<S>, S::Error> {
+        SelfEncryptor::new_with_options(
+            storage,
+            data_map,
+            self.chunking_strategy,
+            self.reference_map,
+            self.cipher_suite,
+        )
+    }
+}
+
+/// The pre-hash of the chunk `offset` positions away from `index`, or the sentinel if that
+/// neighbour would fall outside the file. `offset` is `-1` for the previous chunk, `1` for the
+/// next.
+fn neighbour_pre_hash(chunks: &[ChunkInfo], index: usize, offset: isize) -> Vec<u8> {
+    let neighbour = index as isize + offset;
+    if neighbour < 0 || neighbour as usize >= chunks.len() {
+        SENTINEL_PRE_HASH.to_vec()
+    } else {
+        chunks[neighbour as usize].pre_hash.clone()
+    }
+}
+
+/// Decrypts a set of chunks fetched from `Storage` back into a single plaintext buffer, using
+/// each chunk's neighbours (by index within `chunks`) to derive its pad.
+fn decrypt_chunks(cipher: &dyn Cipher, chunks: &[ChunkInfo], mut encrypted: Vec<Vec<u8>>) -> Vec<u8> {
+    let num_chunks = chunks.len();
+    let mut plaintext = Vec::new();
+    for index in 0..num_chunks {
+        let prev_pre_hash = neighbour_pre_hash(chunks, index, -1);
+        let next_pre_hash = neighbour_pre_hash(chunks, index, 1);
+        cipher.transform(&mut encrypted[index], index, &prev_pre_hash, &next_pre_hash);
+        plaintext.extend_from_slice(&encrypted[index]);
+    }
+    plaintext
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::{self, Display, Formatter};
+
+    #[derive(Debug)]
+    struct MockStorageError;
+
+    impl Display for MockStorageError {
+        fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+            write!(formatter, "mock storage error")
+        }
+    }
+
+    impl std::error::Error for MockStorageError {}
+    impl crate::storage::StorageError for MockStorageError {}
+
+    #[derive(Default)]
+    struct MockStorage {
+        chunks: HashSet<Vec<u8>>,
+        has_calls: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for MockStorage {
+        type Error = MockStorageError;
+
+        async fn get(&mut self, _name: &[u8]) -> Result<Vec<u8>, Self::Error> {
+            Err(MockStorageError)
+        }
+
+        async fn put(&mut self, name: Vec<u8>, _data: Vec<u8>) -> Result<(), Self::Error> {
+            self.chunks.insert(name);
+            Ok(())
+        }
+
+        async fn generate_address(&self, data: &[u8]) -> Vec<u8> {
+            tiny_keccak::sha3_256(data).to_vec()
+        }
+
+        async fn has(&mut self, name: &[u8]) -> Result<bool, Self::Error> {
+            self.has_calls += 1;
+            Ok(self.chunks.contains(name))
+        }
+    }
+
+    fn content_needing_several_chunks() -> Vec<u8> {
+        (0..(crate::MIN_CHUNK_SIZE as usize) * 5)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn close_never_probes_has_without_a_reference_map() {
+        let encryptor = SelfEncryptor::new(MockStorage::default(), DataMap::None).unwrap();
+        encryptor
+            .write(&content_needing_several_chunks(), 0)
+            .await
+            .unwrap();
+        let (_data_map, storage) = encryptor.close().await.unwrap();
+        assert_eq!(storage.has_calls, 0);
+    }
+
+    #[tokio::test]
+    async fn close_probes_has_when_a_reference_map_is_supplied() {
+        let seed = SelfEncryptor::new(MockStorage::default(), DataMap::None).unwrap();
+        seed.write(&content_needing_several_chunks(), 0).await.unwrap();
+        let (reference_map, storage) = seed.close().await.unwrap();
+
+        // Different content from the reference map's: none of this close pass's recomputed
+        // addresses are already in `known_addresses`, so the `||` can't short-circuit and every
+        // chunk genuinely has to ask `Storage::has`.
+        let new_content: Vec<u8> = content_needing_several_chunks()
+            .into_iter()
+            .map(|byte| byte.wrapping_add(1))
+            .collect();
+
+        let encryptor =
+            SelfEncryptor::new_with_reference(storage, DataMap::None, reference_map).unwrap();
+        encryptor.write(&new_content, 0).await.unwrap();
+        let (_data_map, storage) = encryptor.close().await.unwrap();
+        assert!(storage.has_calls > 0);
+    }
+}
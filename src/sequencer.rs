@@ -0,0 +1,56 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! An in-memory buffer of the plaintext currently being built up by a `SelfEncryptor`.
+
+/// Holds the plaintext bytes written to a `SelfEncryptor` so far, growing as needed.
+#[derive(Default)]
+pub struct Sequencer {
+    data: Vec<u8>,
+}
+
+impl Sequencer {
+    /// Creates a sequencer pre-populated with `data`, e.g. the plaintext of an existing
+    /// `DataMap` that is being appended to or overwritten.
+    pub fn new_with_data(data: Vec<u8>) -> Self {
+        Sequencer { data }
+    }
+
+    /// Writes `data` at `offset`, growing the buffer and zero-filling any gap if necessary.
+    pub fn write(&mut self, data: &[u8], offset: usize) {
+        let end = offset + data.len();
+        if end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        self.data[offset..end].copy_from_slice(data);
+    }
+
+    /// Returns the bytes in `[offset, offset + len)`.
+    pub fn read(&self, offset: usize, len: usize) -> Vec<u8> {
+        let end = usize::min(offset + len, self.data.len());
+        if offset >= end {
+            return Vec::new();
+        }
+        self.data[offset..end].to_vec()
+    }
+
+    /// The number of bytes currently held.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns `true` if no bytes have been written.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Consumes the sequencer, returning the underlying bytes.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+}
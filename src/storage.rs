@@ -0,0 +1,47 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use async_trait::async_trait;
+use std::error::Error as StdError;
+use std::fmt::Debug;
+
+/// Marker trait for errors returned by a `Storage` implementation.
+pub trait StorageError: StdError + Send + Sync + Debug {}
+
+/// A place to store and retrieve chunks of data.
+///
+/// Chunks are addressed by the content hash returned from `generate_address`, so implementers
+/// get convergent, dedup-friendly storage for free: two callers encrypting identical chunks will
+/// address them identically.
+///
+/// Requires `Send`: `has`'s default implementation is an `async_trait`-boxed future, which must
+/// be `Send` regardless of which concrete `Storage` ends up calling it, so every implementer has
+/// to be `Send` too.
+#[async_trait]
+pub trait Storage: Send {
+    /// The error type returned by this storage backend.
+    type Error: StorageError;
+
+    /// Retrieves the chunk previously stored under `name`.
+    async fn get(&mut self, name: &[u8]) -> Result<Vec<u8>, Self::Error>;
+
+    /// Stores `data` under `name`, overwriting any existing chunk at that address.
+    async fn put(&mut self, name: Vec<u8>, data: Vec<u8>) -> Result<(), Self::Error>;
+
+    /// Derives the address a chunk of `data` will be stored/retrieved under.
+    async fn generate_address(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Returns whether a chunk already exists under `name`, without returning its contents.
+    ///
+    /// The default implementation probes via `get`, so it costs a full fetch; implementers
+    /// backed by a store that can answer existence more cheaply (e.g. a `stat`-like call) should
+    /// override this.
+    async fn has(&mut self, name: &[u8]) -> Result<bool, Self::Error> {
+        Ok(self.get(name).await.is_ok())
+    }
+}
@@ -0,0 +1,21 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Small helpers shared between examples, tests and callers of this crate.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Serialises `value` using this crate's on-disk `DataMap` encoding.
+pub fn serialise<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("Serialisation should not fail.")
+}
+
+/// Deserialises bytes previously produced by `serialise`, returning `None` on failure.
+pub fn deserialise<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+    bincode::deserialize(data).ok()
+}
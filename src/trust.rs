@@ -0,0 +1,246 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! A reusable guard `Storage` implementers can use to refuse unsafe chunk directories.
+//!
+//! Chunk names are convergent content hashes, so anyone able to write into a chunk store can
+//! pre-seed a poisoned chunk at an address they predict a victim will also write. A chunk
+//! directory (and its ancestors) that is writable by anyone other than its owner can't be
+//! trusted, the same concern `fs-mistrust` addresses for Tor's on-disk state. [`ensure_trusted`]
+//! walks a path upward checking ownership and write permissions, stopping as soon as it reaches a
+//! directory owned by root that isn't writable by anyone else (e.g. `/usr`), or a root-owned,
+//! sticky, world-writable directory (e.g. `/tmp`) — `fs-mistrust` treats both as acceptable trust
+//! boundaries, the sticky bit making the latter safe despite being world-writable, since only a
+//! file's own owner (or root) can rename/delete it there. Both boundaries apply only when the
+//! process itself is not running as root: for a root process, root-owned directories are held to
+//! the same rule as its own, so a group- or world-writable one such as `/tmp` is rejected. It's
+//! meant to be called by a
+//! `Storage::get`/`put` implementation before touching disk.
+//!
+//! The check can be disabled by setting the [`DISABLE_TRUST_CHECK_ENV_VAR`] environment
+//! variable, since CI containers and sandboxes commonly run as a user that legitimately doesn't
+//! own `/tmp` in the way this check expects.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+/// Set this environment variable (to any value) to skip the directory trust check entirely.
+pub const DISABLE_TRUST_CHECK_ENV_VAR: &str = "SELF_ENCRYPTION_DISABLE_TRUST_CHECK";
+
+/// A chunk directory (or one of its ancestors) failed the trust check.
+#[derive(Debug)]
+pub enum TrustError {
+    /// `path` is owned by a user other than the one running this process.
+    NotOwnedByUser {
+        /// The directory that failed the check.
+        path: PathBuf,
+    },
+    /// `path` is writable by users other than its owner.
+    GroupOrWorldWritable {
+        /// The directory that failed the check.
+        path: PathBuf,
+    },
+    /// `path` or one of its ancestors could not be inspected.
+    Io {
+        /// The directory that could not be inspected.
+        path: PathBuf,
+        /// The underlying I/O error.
+        source: io::Error,
+    },
+}
+
+impl Display for TrustError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self {
+            TrustError::NotOwnedByUser { path } => {
+                write!(formatter, "{} is not owned by the current user", path.display())
+            }
+            TrustError::GroupOrWorldWritable { path } => write!(
+                formatter,
+                "{} is writable by a group or by everyone",
+                path.display()
+            ),
+            TrustError::Io { path, source } => {
+                write!(formatter, "failed to inspect {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TrustError {}
+
+/// Checks that `path` and its ancestors, up to the first trusted boundary, are owned by the
+/// current user (or root) and not writable by anyone else, returning the first violation found
+/// (closest to `path` first).
+///
+/// Does nothing and returns `Ok(())` if [`DISABLE_TRUST_CHECK_ENV_VAR`] is set, or on non-Unix
+/// platforms where ownership/permission bits aren't meaningful in the same way.
+pub fn ensure_trusted(path: &Path) -> Result<(), TrustError> {
+    if std::env::var_os(DISABLE_TRUST_CHECK_ENV_VAR).is_some() {
+        return Ok(());
+    }
+    check_ancestors(path)
+}
+
+const ROOT_UID: u32 = 0;
+// Group-write and other-write.
+const GROUP_OR_WORLD_WRITABLE: u32 = 0o022;
+// Other-write and the sticky bit.
+const STICKY_WORLD_WRITABLE: u32 = 0o1002;
+
+/// Why a single ancestor directory fails the check.
+#[derive(Debug, Eq, PartialEq)]
+enum Violation {
+    /// Owned by neither the current user nor root.
+    NotOwnedByUser,
+    /// Writable by a group or by everyone (whether owned by the current user or by root).
+    GroupOrWorldWritable,
+}
+
+/// What a single ancestor's ownership/mode means for the walk.
+#[derive(Debug, Eq, PartialEq)]
+enum AncestorOutcome {
+    /// Fine as far as it goes; keep walking upward.
+    Continue,
+    /// A safe trust boundary; stop walking, the whole path is trusted.
+    Stop,
+    /// This ancestor fails the check.
+    Violation(Violation),
+}
+
+/// Classifies a single ancestor directory given its owning `uid` and permission `mode`, against
+/// the uid running this process. Pure so the decision logic can be unit tested without needing
+/// real root-owned directories to inspect.
+fn classify_ancestor(current_uid: u32, uid: u32, mode: u32) -> AncestorOutcome {
+    if uid == current_uid {
+        if mode & GROUP_OR_WORLD_WRITABLE != 0 {
+            return AncestorOutcome::Violation(Violation::GroupOrWorldWritable);
+        }
+        return AncestorOutcome::Continue;
+    }
+
+    if uid != ROOT_UID {
+        return AncestorOutcome::Violation(Violation::NotOwnedByUser);
+    }
+
+    // Root-owned: a sticky, world-writable directory (e.g. /tmp) is safe because only an entry's
+    // own owner (or root) can rename/delete it there; a directory not writable by anyone else
+    // (e.g. /, /usr, /home) is trivially safe too. Either is a boundary worth stopping at, since
+    // everything above it is irrelevant to whether our own chunk directory is safe. A root-owned
+    // directory that's writable by others but *not* sticky gets neither pass, and is rejected as
+    // writable below — not as merely "not owned by us", since it is.
+    if mode & STICKY_WORLD_WRITABLE == STICKY_WORLD_WRITABLE || mode & GROUP_OR_WORLD_WRITABLE == 0 {
+        return AncestorOutcome::Stop;
+    }
+    AncestorOutcome::Violation(Violation::GroupOrWorldWritable)
+}
+
+#[cfg(unix)]
+fn check_ancestors(path: &Path) -> Result<(), TrustError> {
+    let current_uid = nix::unistd::getuid().as_raw();
+    for ancestor in path.ancestors() {
+        let metadata = std::fs::metadata(ancestor).map_err(|source| TrustError::Io {
+            path: ancestor.to_owned(),
+            source,
+        })?;
+
+        match classify_ancestor(current_uid, metadata.uid(), metadata.mode()) {
+            AncestorOutcome::Continue => continue,
+            AncestorOutcome::Stop => return Ok(()),
+            AncestorOutcome::Violation(Violation::GroupOrWorldWritable) => {
+                return Err(TrustError::GroupOrWorldWritable {
+                    path: ancestor.to_owned(),
+                });
+            }
+            AncestorOutcome::Violation(Violation::NotOwnedByUser) => {
+                return Err(TrustError::NotOwnedByUser {
+                    path: ancestor.to_owned(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_ancestors(_path: &Path) -> Result<(), TrustError> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn root_owned_sticky_world_writable_dir_is_a_safe_boundary() {
+        assert_eq!(classify_ancestor(1000, ROOT_UID, 0o1777), AncestorOutcome::Stop);
+    }
+
+    #[test]
+    fn root_owned_non_writable_dir_is_a_safe_boundary() {
+        assert_eq!(classify_ancestor(1000, ROOT_UID, 0o755), AncestorOutcome::Stop);
+    }
+
+    #[test]
+    fn root_owned_group_writable_non_sticky_dir_is_rejected_as_writable_not_unowned() {
+        // Owned correctly (by root), just unsafely permissioned: must route to
+        // `GroupOrWorldWritable`, not the misleading `NotOwnedByUser`.
+        assert_eq!(
+            classify_ancestor(1000, ROOT_UID, 0o775),
+            AncestorOutcome::Violation(Violation::GroupOrWorldWritable)
+        );
+    }
+
+    #[test]
+    fn other_users_directory_is_rejected_as_not_owned() {
+        assert_eq!(
+            classify_ancestor(1000, 2000, 0o700),
+            AncestorOutcome::Violation(Violation::NotOwnedByUser)
+        );
+    }
+
+    #[test]
+    fn current_users_writable_directory_is_rejected() {
+        assert_eq!(
+            classify_ancestor(1000, 1000, 0o775),
+            AncestorOutcome::Violation(Violation::GroupOrWorldWritable)
+        );
+    }
+
+    #[test]
+    fn current_users_private_directory_continues_the_walk() {
+        assert_eq!(classify_ancestor(1000, 1000, 0o700), AncestorOutcome::Continue);
+    }
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    #[test]
+    fn check_ancestors_rejects_a_real_group_writable_directory() {
+        let dir = std::env::temp_dir().join(format!(
+            "self_encryption_trust_test_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+
+        // The directory is owned by whichever uid is running this test, so it's always caught by
+        // the `uid == current_uid` branch regardless of what that uid happens to be.
+        let result = check_ancestors(&dir);
+
+        fs::remove_dir_all(&dir).ok();
+        assert!(matches!(result, Err(TrustError::GroupOrWorldWritable { .. })));
+    }
+}